@@ -1,12 +1,16 @@
 extern crate mdns;
 
+use std::collections::BTreeMap;
 use std::net::IpAddr;
+use std::time::Duration;
 
 use log::info;
 
+use crate::discovery::ipversion::IpVersion;
+
 const DEFAULT_NAME: &str = "Unnamed";
 
-// TODO: Consider manually implementing Hash and Eq, so that (ip_addr, port)
+// TODO: Consider manually implementing Hash and Eq, so that (addresses, port)
 // defines a unique device. If this happens, the device discovery cache will
 // need handling for replacing an existing DeviceRecord in its discovered set.
 
@@ -30,8 +34,10 @@ pub struct DeviceRecord {
     /// mDNS service address of the device
     pub ptr: Option<String>,
 
-    /// IPv4/IPv6 address of the device
-    pub ip_addr: IpAddr,
+    /// Every IPv4/IPv6 address advertised by the device's A/AAAA records,
+    /// in the order they were seen. Use [`address`](Self::address) to pick
+    /// one according to an [`IpVersion`] preference.
+    pub addresses: Vec<IpAddr>,
 
     /// Port on which the service is running
     pub port: u16,
@@ -60,39 +66,105 @@ pub struct DeviceRecord {
 
     /// Friendly name assigned by the device owner, e.g., `Living room`
     pub friendly_name: Option<String>,
+
+    /// Full set of key/value pairs from the TXT record, verbatim
+    ///
+    /// The typed fields above cover the Google Cast TXT keys. Other
+    /// services (e.g. HomeKit's `id`/`c#`/`sf`) are only available here.
+    pub txt: BTreeMap<String, String>,
 }
 
 impl DeviceRecord {
-    /// User-friendly display name
-    pub fn display_name(&self) -> String {
+    /// User-friendly display name, with the address picked according to an
+    /// [`IpVersion`] preference; see [`address`](Self::address).
+    pub fn display_name(&self, preference: IpVersion) -> String {
         let default_name = &DEFAULT_NAME.to_string();
         let friendly_name = self.friendly_name.as_ref().unwrap_or(default_name);
-        format!("{} ({})", friendly_name, self.ip_addr)
+        match self.address(preference) {
+            Some(addr) => format!("{} ({})", friendly_name, addr),
+            None => friendly_name.clone(),
+        }
+    }
+
+    /// Pick one of [`addresses`](Self::addresses) matching an [`IpVersion`]
+    /// preference, for use when connecting to the device.
+    pub fn address(&self, preference: IpVersion) -> Option<IpAddr> {
+        match preference {
+            IpVersion::V4 => self.addresses.iter().find(|addr| addr.is_ipv4()).copied(),
+            IpVersion::V6 => self.addresses.iter().find(|addr| addr.is_ipv6()).copied(),
+            IpVersion::Both => self.addresses.first().copied(),
+        }
+    }
+
+    /// Constructs a [`DeviceRecord`] from a single mDNS response, most
+    /// likely from a discovery run against the Google Cast mDNS service
+    /// name.
+    ///
+    /// Responders that split a device's A/AAAA, SRV, and TXT records across
+    /// multiple packets won't materialize here; use [`PartialDeviceRecord`]
+    /// to merge those as they arrive.
+    pub fn from_mdns(response: &mdns::Response, ip_version: IpVersion) -> Option<DeviceRecord> {
+        let mut partial = PartialDeviceRecord::default();
+        partial.merge(response, ip_version);
+
+        let record = partial.finish();
+        if record.is_none() {
+            info!("Incomplete mDNS response (missing address or SRV port); invalid device");
+        }
+        record
     }
+}
+
+/// Accumulates a [`DeviceRecord`] across one or more mDNS responses
+///
+/// Responders commonly split a single device's A/AAAA, SRV, and TXT records
+/// across separate packets rather than a single [`mdns::Response`]. Call
+/// [`merge`](Self::merge) with each response naming the same PTR/instance
+/// name as it arrives, keeping fields already known from earlier packets,
+/// and [`finish`](Self::finish) once an address and port are both known.
+#[derive(Clone, Debug, Default)]
+pub struct PartialDeviceRecord {
+    ptr: Option<String>,
+    addresses: Vec<IpAddr>,
+    port: Option<u16>,
+    device_uuid: Option<String>,
+    model: Option<String>,
+    version: Option<String>,
+    icon_path: Option<String>,
+    certificate_authority: Option<String>,
+    friendly_name: Option<String>,
+    txt: BTreeMap<String, String>,
+
+    /// Smallest non-zero TTL seen on an A/AAAA/SRV/TXT/PTR record so far
+    ttl: Option<Duration>,
 
-    /// Constructs a [`DeviceRecord`] from an mDNS response, most likely from
-    /// a discovery run against the Google Cast mDNS service name.
-    pub fn from_mdns(response: &mdns::Response) -> Option<DeviceRecord> {
-        let mut ptr: Option<String> = Default::default();
-        let mut ip_addr: Option<IpAddr> = Default::default();
-        let mut port: Option<u16> = Default::default();
-        let mut device_uuid: Option<String> = Default::default();
-        let mut model: Option<String> = Default::default();
-        let mut version: Option<String> = Default::default();
-        let mut icon_path: Option<String> = Default::default();
-        let mut certificate_authority: Option<String> = Default::default();
-        let mut friendly_name: Option<String> = Default::default();
+    /// Whether an A/AAAA/SRV/TXT/PTR record with TTL 0 (an RFC 6762 goodbye,
+    /// a.k.a. cache-flush) has been seen
+    goodbye: bool,
+}
 
+impl PartialDeviceRecord {
+    /// Merge in any fields carried by `response`, keeping fields already
+    /// known from earlier packets. A/AAAA records outside `ip_version` are
+    /// ignored.
+    pub fn merge(&mut self, response: &mdns::Response, ip_version: IpVersion) {
         for dns_record in response.records() {
             match dns_record.kind {
                 mdns::RecordKind::A(addr) => {
-                    ip_addr = Some(addr.into());
+                    if ip_version.accepts_v4() {
+                        self.push_address(addr.into());
+                        self.observe_ttl(dns_record.ttl);
+                    }
                 }
                 mdns::RecordKind::AAAA(addr) => {
-                    ip_addr = Some(addr.into());
+                    if ip_version.accepts_v6() {
+                        self.push_address(addr.into());
+                        self.observe_ttl(dns_record.ttl);
+                    }
                 }
                 mdns::RecordKind::SRV { port: srv_port, .. } => {
-                    port = Some(srv_port);
+                    self.port = Some(srv_port);
+                    self.observe_ttl(dns_record.ttl);
                 }
                 mdns::RecordKind::TXT(ref records) => {
                     for record in records.iter() {
@@ -104,45 +176,120 @@ impl DeviceRecord {
                         }
 
                         let key = splits[0];
-                        let val = Some(String::from(splits[1]));
+                        let val = String::from(splits[1]);
                         match key {
-                            "ca" => certificate_authority = val,
-                            "fn" => friendly_name = val,
-                            "ic" => icon_path = val,
-                            "id" => device_uuid = val,
-                            "md" => model = val,
-                            "ve" => version = val,
+                            "ca" => self.certificate_authority = Some(val.clone()),
+                            "fn" => self.friendly_name = Some(val.clone()),
+                            "ic" => self.icon_path = Some(val.clone()),
+                            "id" => self.device_uuid = Some(val.clone()),
+                            "md" => self.model = Some(val.clone()),
+                            "ve" => self.version = Some(val.clone()),
                             _ => (),
                         }
+                        self.txt.insert(String::from(key), val);
                     }
+                    self.observe_ttl(dns_record.ttl);
                 }
                 mdns::RecordKind::PTR(ref string) => {
-                    ptr = Some(string.to_owned());
+                    self.ptr = Some(string.to_owned());
+                    self.observe_ttl(dns_record.ttl);
                 }
                 _ => (),
             }
         }
+    }
 
-        if let Some(ip_addr) = ip_addr {
-            if let Some(port) = port {
-                Some(DeviceRecord {
-                    ip_addr,
-                    port,
-                    ptr,
-                    device_uuid,
-                    model,
-                    version,
-                    icon_path,
-                    certificate_authority,
-                    friendly_name,
-                })
-            } else {
-                info!("No SRV port record found; invalid device");
-                None
-            }
-        } else {
-            info!("No A/AAAA port record found; invalid device");
-            None
+    /// Materialize a [`DeviceRecord`] if at least one address and a port
+    /// have both been seen across the merged responses, otherwise `None`.
+    pub fn finish(&self) -> Option<DeviceRecord> {
+        if self.addresses.is_empty() {
+            return None;
         }
+        let port = self.port?;
+
+        Some(DeviceRecord {
+            ptr: self.ptr.clone(),
+            addresses: self.addresses.clone(),
+            port,
+            device_uuid: self.device_uuid.clone(),
+            model: self.model.clone(),
+            version: self.version.clone(),
+            icon_path: self.icon_path.clone(),
+            certificate_authority: self.certificate_authority.clone(),
+            friendly_name: self.friendly_name.clone(),
+            txt: self.txt.clone(),
+        })
+    }
+
+    /// Whether a TTL of 0 (an RFC 6762 goodbye) has been seen on this
+    /// device's A/AAAA/SRV/TXT/PTR records, meaning the responder wants it forgotten
+    /// immediately rather than aged out.
+    pub fn is_goodbye(&self) -> bool {
+        self.goodbye
+    }
+
+    /// Smallest TTL seen on this device's A/AAAA/SRV/TXT/PTR records, or `default`
+    /// if none carried one. Meaningless once [`is_goodbye`](Self::is_goodbye)
+    /// is true.
+    pub fn ttl(&self, default: Duration) -> Duration {
+        self.ttl.unwrap_or(default)
+    }
+
+    fn push_address(&mut self, addr: IpAddr) {
+        if !self.addresses.contains(&addr) {
+            self.addresses.push(addr);
+        }
+    }
+
+    /// A TTL of 0 is a goodbye/cache-flush, not "expire instantly"; record
+    /// it separately rather than folding it into the minimum, which would
+    /// otherwise let one flushed record pin every other TTL to 0.
+    fn observe_ttl(&mut self, ttl: u32) {
+        if ttl == 0 {
+            self.goodbye = true;
+            return;
+        }
+
+        let ttl = Duration::from_secs(u64::from(ttl));
+        self.ttl = Some(match self.ttl {
+            Some(current) => current.min(ttl),
+            None => ttl,
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn observe_ttl_tracks_the_minimum() {
+        let mut partial = PartialDeviceRecord::default();
+        partial.observe_ttl(120);
+        partial.observe_ttl(60);
+        partial.observe_ttl(90);
+
+        assert!(!partial.is_goodbye());
+        assert_eq!(partial.ttl(Duration::from_secs(1)), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn observe_ttl_zero_is_a_goodbye_not_an_instant_expiry() {
+        let mut partial = PartialDeviceRecord::default();
+        partial.observe_ttl(60);
+        partial.observe_ttl(0);
+
+        assert!(partial.is_goodbye());
+        // The goodbye record's TTL-0 must not pull the minimum down to zero.
+        assert_eq!(partial.ttl(Duration::from_secs(1)), Duration::from_secs(60));
+    }
+
+    #[test]
+    fn ttl_falls_back_to_default_when_nothing_was_observed() {
+        let partial = PartialDeviceRecord::default();
+        assert_eq!(
+            partial.ttl(Duration::from_secs(42)),
+            Duration::from_secs(42)
+        );
     }
 }
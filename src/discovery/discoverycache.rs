@@ -1,6 +1,8 @@
 extern crate mdns;
 
+use std::collections::HashMap;
 use std::collections::HashSet;
+use std::sync::mpsc;
 use std::sync::Arc;
 use std::sync::Mutex;
 use std::thread;
@@ -8,33 +10,400 @@ use std::time::{Duration, Instant};
 
 use log::debug;
 
-use crate::discovery::device::DeviceRecord;
+use crate::discovery::device::{DeviceRecord, PartialDeviceRecord};
+use crate::discovery::event::DiscoveryEvent;
+use crate::discovery::ipversion::IpVersion;
+
+/// TTL assumed for a device when no record in its response carried one
+const DEFAULT_TTL: Duration = Duration::from_secs(120);
+
+/// Default multiplier applied to a device's TTL before it is evicted,
+/// per common mDNS cache-flush practice of waiting beyond the advertised TTL
+const DEFAULT_GRACE_MULTIPLIER: u32 = 2;
+
+/// How often the cache checks for expired entries
+const SWEEP_INTERVAL: Duration = Duration::from_secs(1);
+
+/// How long a [`PartialDeviceRecord`] may go without a merge before it's
+/// dropped, so a responder that starts announcing but never completes (or
+/// stops re-announcing) doesn't accumulate forever under [`watch`](DeviceDiscoveryCache::watch).
+const PARTIAL_RECORD_TTL: Duration = Duration::from_secs(300);
+
+/// Key used to tell whether two [`DeviceRecord`]s refer to the same device,
+/// preferring the device's UUID and falling back to its address and port.
+///
+/// Addresses are sorted first: a responder isn't guaranteed to list its
+/// A/AAAA records in the same order on every announcement, and the raw
+/// `Vec` order isn't part of a device's identity.
+fn device_key(record: &DeviceRecord) -> String {
+    match &record.device_uuid {
+        Some(uuid) => uuid.clone(),
+        None => {
+            let mut addresses = record.addresses.clone();
+            addresses.sort();
+            format!("{:?}:{}", addresses, record.port)
+        }
+    }
+}
+
+/// Compare two records for equality, ignoring the order their addresses
+/// were reported in (see [`device_key`]).
+fn records_equal(a: &DeviceRecord, b: &DeviceRecord) -> bool {
+    let mut a_addresses = a.addresses.clone();
+    let mut b_addresses = b.addresses.clone();
+    a_addresses.sort();
+    b_addresses.sort();
+
+    a.ptr == b.ptr
+        && a_addresses == b_addresses
+        && a.port == b.port
+        && a.device_uuid == b.device_uuid
+        && a.model == b.model
+        && a.version == b.version
+        && a.icon_path == b.icon_path
+        && a.certificate_authority == b.certificate_authority
+        && a.friendly_name == b.friendly_name
+        && a.txt == b.txt
+}
+
+/// PTR/instance name a response's records should be merged under, so that a
+/// device's A/AAAA, SRV, and TXT records split across separate packets are
+/// accumulated into the same [`PartialDeviceRecord`].
+///
+/// A response carrying only A/AAAA records (e.g. a standalone
+/// re-announcement after an IP change) has no instance name of its own -
+/// its records' `name` is the SRV target hostname instead. Such a response
+/// is keyed by that hostname via `hostname_aliases`, which remembers which
+/// instance name a hostname belongs to once a response naming both has been
+/// seen; falling back to the bare hostname if the alias isn't known yet.
+fn response_key(
+    response: &mdns::Response,
+    hostname_aliases: &HashMap<String, String>,
+) -> Option<String> {
+    response
+        .records()
+        .find_map(|record| match &record.kind {
+            mdns::RecordKind::PTR(name) => Some(name.clone()),
+            mdns::RecordKind::SRV { .. } | mdns::RecordKind::TXT(_) => Some(record.name.clone()),
+            _ => None,
+        })
+        .or_else(|| {
+            response.records().find_map(|record| match &record.kind {
+                mdns::RecordKind::A(_) | mdns::RecordKind::AAAA(_) => Some(
+                    hostname_aliases
+                        .get(&record.name)
+                        .cloned()
+                        .unwrap_or_else(|| record.name.clone()),
+                ),
+                _ => None,
+            })
+        })
+}
+
+/// SRV target hostname carried by `response`, if any, e.g. `device-1234.local`
+fn srv_target(response: &mdns::Response) -> Option<String> {
+    response.records().find_map(|record| match &record.kind {
+        mdns::RecordKind::SRV { target, .. } => Some(target.clone()),
+        _ => None,
+    })
+}
+
+/// Result of merging a response into a [`PartialDeviceRecord`]
+enum MergeOutcome {
+    /// The device is (still) present; cache it under the given TTL
+    Ready(DeviceRecord, Duration),
+
+    /// The responder sent a TTL-0 goodbye; the device should be forgotten
+    /// immediately rather than aged out by the TTL sweep
+    Goodbye(DeviceRecord),
+}
+
+/// A cached [`DeviceRecord`] along with enough information to know when it
+/// should be evicted
+struct CacheEntry {
+    record: DeviceRecord,
+    inserted: Instant,
+    ttl: Duration,
+}
+
+impl CacheEntry {
+    fn new(record: DeviceRecord, ttl: Duration) -> Self {
+        CacheEntry {
+            record,
+            inserted: Instant::now(),
+            ttl,
+        }
+    }
+
+    fn is_expired(&self, grace_multiplier: u32) -> bool {
+        self.inserted.elapsed() > self.ttl * grace_multiplier
+    }
+}
+
+/// A [`PartialDeviceRecord`] along with when it was last merged into
+///
+/// Kept around (rather than discarded the moment it first completes) so
+/// that later packets naming the same PTR/instance name - e.g. a
+/// TXT-only re-announcement, or a lone goodbye record that doesn't resend
+/// the device's address - can still be merged against it. [`PARTIAL_RECORD_TTL`]
+/// bounds how long a partial is kept without being refreshed.
+struct PartialEntry {
+    partial: PartialDeviceRecord,
+    last_merged: Instant,
+}
+
+impl PartialEntry {
+    fn is_expired(&self) -> bool {
+        self.last_merged.elapsed() > PARTIAL_RECORD_TTL
+    }
+}
+
+impl Default for PartialEntry {
+    fn default() -> Self {
+        PartialEntry {
+            partial: PartialDeviceRecord::default(),
+            last_merged: Instant::now(),
+        }
+    }
+}
 
 pub struct DeviceDiscoveryCache {
-    // TODO: Track record time and TTL, and flush entries from the cache.
     service_name: String,
-    device_records: Arc<Mutex<HashSet<DeviceRecord>>>,
+    device_records: Arc<Mutex<HashMap<String, CacheEntry>>>,
+    partial_records: Arc<Mutex<HashMap<String, PartialEntry>>>,
+
+    /// SRV target hostname -> instance-name key of the [`PartialEntry`] it
+    /// belongs to; see [`response_key`].
+    hostname_aliases: Arc<Mutex<HashMap<String, String>>>,
+    event_tx: Arc<Mutex<Option<mpsc::Sender<DiscoveryEvent>>>>,
     poll_start_time: Instant,
     poll_finish_time: Arc<Mutex<Instant>>,
     pub timeout_ms: Duration,
+
+    /// Multiplier applied to a device's TTL before it is evicted from the
+    /// cache; see [`DEFAULT_GRACE_MULTIPLIER`]
+    grace_multiplier: Arc<Mutex<u32>>,
+
+    /// Which IP address family to discover; see [`IpVersion`]
+    ip_version: IpVersion,
 }
 
 impl DeviceDiscoveryCache {
     /// Discovery timeout in milliseconds
     const DEFAULT_TIMEOUT_MS: Duration = Duration::from_millis(2000);
 
-    pub fn new(service_name: String) -> Self {
+    pub fn new(service_name: String, ip_version: IpVersion) -> Self {
         let now = Instant::now();
+        let device_records = Arc::new(Mutex::new(HashMap::new()));
+        let partial_records = Arc::new(Mutex::new(HashMap::new()));
+        let hostname_aliases = Arc::new(Mutex::new(HashMap::new()));
+        let event_tx = Arc::new(Mutex::new(None));
+        let grace_multiplier = Arc::new(Mutex::new(DEFAULT_GRACE_MULTIPLIER));
+
+        Self::spawn_sweep(
+            Arc::clone(&device_records),
+            Arc::clone(&event_tx),
+            Arc::clone(&grace_multiplier),
+        );
+        Self::spawn_partial_sweep(Arc::clone(&partial_records), Arc::clone(&hostname_aliases));
 
         DeviceDiscoveryCache {
             service_name,
-            device_records: Arc::new(Mutex::new(HashSet::new())),
+            device_records,
+            partial_records,
+            hostname_aliases,
+            event_tx,
             poll_start_time: now,
             poll_finish_time: Arc::new(Mutex::new(now)),
             timeout_ms: Self::DEFAULT_TIMEOUT_MS,
+            grace_multiplier,
+            ip_version,
+        }
+    }
+
+    /// Current grace multiplier; see [`set_grace_multiplier`](Self::set_grace_multiplier)
+    pub fn grace_multiplier(&self) -> u32 {
+        *self.grace_multiplier.lock().unwrap()
+    }
+
+    /// Set the multiplier applied to a device's TTL before it is evicted
+    /// from the cache. Defaults to [`DEFAULT_GRACE_MULTIPLIER`].
+    pub fn set_grace_multiplier(&self, multiplier: u32) {
+        *self.grace_multiplier.lock().unwrap() = multiplier;
+    }
+
+    /// Merge `response` into the matching in-flight [`PartialDeviceRecord`],
+    /// returning the device once its address and port are both known. This is what lets a device
+    /// whose A/AAAA, SRV, and TXT records arrive in separate packets still
+    /// be discovered.
+    ///
+    /// The partial is kept (not removed) once it completes, so that a later
+    /// packet naming the same PTR/instance name - e.g. a TXT-only
+    /// re-announcement - merges against the address/port already known
+    /// instead of starting over incomplete; see [`PartialEntry`]. It's only
+    /// removed here once the device sends a goodbye, and otherwise by
+    /// [`spawn_partial_sweep`] once it goes stale.
+    ///
+    /// Goodbye is checked before requiring a complete [`DeviceRecord`]: a
+    /// real goodbye is typically a lone PTR (or previously-cached SRV/A)
+    /// record with TTL 0 that doesn't resend the device's address, so
+    /// whether it can still be evicted depends only on whether this partial
+    /// has a retained address/port from an earlier packet, not on whether
+    /// *this* packet completes one.
+    fn merge_response(
+        partial_records: &Mutex<HashMap<String, PartialEntry>>,
+        hostname_aliases: &Mutex<HashMap<String, String>>,
+        response: &mdns::Response,
+        ip_version: IpVersion,
+    ) -> Option<MergeOutcome> {
+        let mut aliases = hostname_aliases.lock().unwrap();
+        let key = response_key(response, &aliases)?;
+        if let Some(target) = srv_target(response) {
+            aliases.insert(target, key.clone());
+        }
+        drop(aliases);
+
+        let mut partials = partial_records.lock().unwrap();
+        let entry = partials.entry(key.clone()).or_default();
+        entry.last_merged = Instant::now();
+        entry.partial.merge(response, ip_version);
+
+        if entry.partial.is_goodbye() {
+            let record = entry.partial.finish();
+            partials.remove(&key);
+            drop(partials);
+            hostname_aliases.lock().unwrap().retain(|_, v| v != &key);
+            return record.map(MergeOutcome::Goodbye);
+        }
+
+        let record = entry.partial.finish()?;
+        Some(MergeOutcome::Ready(record, entry.partial.ttl(DEFAULT_TTL)))
+    }
+
+    /// Remove a device announced as gone (TTL 0 / goodbye) from the cache,
+    /// reporting it as [`DiscoveryEvent::Removed`] if a watcher is attached.
+    fn evict_and_notify(
+        device_records: &Mutex<HashMap<String, CacheEntry>>,
+        event_tx: &Mutex<Option<mpsc::Sender<DiscoveryEvent>>>,
+        record: DeviceRecord,
+    ) {
+        let removed = {
+            let mut records = device_records.lock().unwrap();
+            records.remove(&device_key(&record))
+        };
+
+        if let Some(entry) = removed {
+            if let Some(tx) = event_tx.lock().unwrap().as_ref() {
+                let _ = tx.send(DiscoveryEvent::Removed(entry.record));
+            }
+        }
+    }
+
+    /// Insert or refresh a freshly-seen device, returning the event (if any)
+    /// that should be reported for it.
+    fn upsert(
+        records: &mut HashMap<String, CacheEntry>,
+        record: DeviceRecord,
+        ttl: Duration,
+    ) -> Option<DiscoveryEvent> {
+        let key = device_key(&record);
+
+        match records.get(&key) {
+            None => {
+                let event = DiscoveryEvent::Added(record.clone());
+                records.insert(key, CacheEntry::new(record, ttl));
+                Some(event)
+            }
+            Some(existing) if !records_equal(&existing.record, &record) => {
+                let event = DiscoveryEvent::Updated(record.clone());
+                records.insert(key, CacheEntry::new(record, ttl));
+                Some(event)
+            }
+            Some(_) => {
+                // Re-announced before expiry; refresh its deadline without
+                // duplicating or reporting it.
+                records.insert(key, CacheEntry::new(record, ttl));
+                None
+            }
+        }
+    }
+
+    /// Insert or refresh a freshly-seen device and, if that produced an
+    /// event, report it to whichever [`watch`](Self::watch) channel is
+    /// attached. Shared by [`start_discovery`](Self::start_discovery) and
+    /// [`watch`](Self::watch) so devices discovered by either path reach
+    /// the same watcher.
+    fn upsert_and_notify(
+        device_records: &Mutex<HashMap<String, CacheEntry>>,
+        event_tx: &Mutex<Option<mpsc::Sender<DiscoveryEvent>>>,
+        record: DeviceRecord,
+        ttl: Duration,
+    ) {
+        let event = {
+            let mut records = device_records.lock().unwrap();
+            Self::upsert(&mut records, record, ttl)
+        };
+
+        if let Some(event) = event {
+            if let Some(tx) = event_tx.lock().unwrap().as_ref() {
+                let _ = tx.send(event);
+            }
         }
     }
 
+    /// Periodically remove entries whose TTL (times the grace multiplier)
+    /// has elapsed since they were last seen, reporting each eviction as a
+    /// [`DiscoveryEvent::Removed`] if a watcher is attached.
+    fn spawn_sweep(
+        device_records: Arc<Mutex<HashMap<String, CacheEntry>>>,
+        event_tx: Arc<Mutex<Option<mpsc::Sender<DiscoveryEvent>>>>,
+        grace_multiplier: Arc<Mutex<u32>>,
+    ) {
+        thread::spawn(move || loop {
+            thread::sleep(SWEEP_INTERVAL);
+
+            let mut records = device_records.lock().unwrap();
+            let grace_multiplier = *grace_multiplier.lock().unwrap();
+            let expired_keys: Vec<String> = records
+                .iter()
+                .filter(|(_, entry)| entry.is_expired(grace_multiplier))
+                .map(|(key, _)| key.clone())
+                .collect();
+
+            if expired_keys.is_empty() {
+                continue;
+            }
+
+            let tx = event_tx.lock().unwrap();
+            for key in expired_keys {
+                if let Some(entry) = records.remove(&key) {
+                    if let Some(tx) = tx.as_ref() {
+                        let _ = tx.send(DiscoveryEvent::Removed(entry.record));
+                    }
+                }
+            }
+        });
+    }
+
+    /// Periodically drop [`PartialEntry`]s that haven't been merged into
+    /// recently, so a responder that starts announcing but never completes
+    /// (or stops re-announcing) doesn't accumulate forever. Also drops
+    /// `hostname_aliases` entries pointing at a dropped partial.
+    fn spawn_partial_sweep(
+        partial_records: Arc<Mutex<HashMap<String, PartialEntry>>>,
+        hostname_aliases: Arc<Mutex<HashMap<String, String>>>,
+    ) {
+        thread::spawn(move || loop {
+            thread::sleep(SWEEP_INTERVAL);
+
+            let mut partials = partial_records.lock().unwrap();
+            partials.retain(|_, entry| !entry.is_expired());
+
+            let mut aliases = hostname_aliases.lock().unwrap();
+            aliases.retain(|_, key| partials.contains_key(key));
+        });
+    }
+
     /// Search for advertised mDNS devices
     ///
     /// This starts an mDNS discovery poll in a thread, updating the cache's
@@ -45,8 +414,12 @@ impl DeviceDiscoveryCache {
 
             let poll_time = Arc::clone(&self.poll_finish_time);
             let data = Arc::clone(&self.device_records);
+            let partial_records = Arc::clone(&self.partial_records);
+            let hostname_aliases = Arc::clone(&self.hostname_aliases);
+            let event_tx = Arc::clone(&self.event_tx);
             let timeout_ms = self.timeout_ms;
             let service_name = self.service_name.clone();
+            let ip_version = self.ip_version;
 
             thread::spawn(move || {
                 let responses = mdns::discover::all(service_name)
@@ -54,12 +427,24 @@ impl DeviceDiscoveryCache {
                     .timeout(timeout_ms);
 
                 // Create devices from mDNS responses and insert them into the
-                // device discovery cache.
+                // device discovery cache. A concurrent watch() subscriber (if
+                // any) is notified the same as if it had read the response
+                // itself.
                 for response in responses {
                     if let Ok(response) = response {
-                        if let Some(record) = DeviceRecord::from_mdns(&response) {
-                            let mut records = data.lock().unwrap();
-                            records.insert(record.clone());
+                        match Self::merge_response(
+                            &partial_records,
+                            &hostname_aliases,
+                            &response,
+                            ip_version,
+                        ) {
+                            Some(MergeOutcome::Ready(record, ttl)) => {
+                                Self::upsert_and_notify(&data, &event_tx, record, ttl);
+                            }
+                            Some(MergeOutcome::Goodbye(record)) => {
+                                Self::evict_and_notify(&data, &event_tx, record);
+                            }
+                            None => {}
                         }
                     }
                 }
@@ -78,8 +463,186 @@ impl DeviceDiscoveryCache {
         self.poll_start_time > *poll_finish_time
     }
 
+    /// Continuously watch for advertised mDNS devices
+    ///
+    /// Unlike [`start_discovery`](Self::start_discovery), this keeps the
+    /// underlying mDNS listener running indefinitely instead of stopping
+    /// after a timeout. Every incoming [`DeviceRecord`] is diffed against
+    /// the cache and reported on the returned channel as a
+    /// [`DiscoveryEvent::Added`] or [`DiscoveryEvent::Updated`], and entries
+    /// evicted by the cache's TTL sweep are reported as
+    /// [`DiscoveryEvent::Removed`]. This lets callers such as a GUI device
+    /// list react to changes without re-polling.
+    pub fn watch(&mut self) -> mpsc::Receiver<DiscoveryEvent> {
+        let (tx, rx) = mpsc::channel();
+        *self.event_tx.lock().unwrap() = Some(tx);
+
+        let data = Arc::clone(&self.device_records);
+        let partial_records = Arc::clone(&self.partial_records);
+        let hostname_aliases = Arc::clone(&self.hostname_aliases);
+        let event_tx = Arc::clone(&self.event_tx);
+        let service_name = self.service_name.clone();
+        let ip_version = self.ip_version;
+
+        thread::spawn(move || {
+            let listener = match mdns::discover::all(service_name) {
+                Ok(discovery) => discovery.listen(),
+                Err(_) => return,
+            };
+
+            for response in listener {
+                if let Ok(response) = response {
+                    match Self::merge_response(
+                        &partial_records,
+                        &hostname_aliases,
+                        &response,
+                        ip_version,
+                    ) {
+                        Some(MergeOutcome::Ready(record, ttl)) => {
+                            Self::upsert_and_notify(&data, &event_tx, record, ttl);
+                        }
+                        Some(MergeOutcome::Goodbye(record)) => {
+                            Self::evict_and_notify(&data, &event_tx, record);
+                        }
+                        None => {}
+                    }
+                }
+            }
+        });
+
+        rx
+    }
+
     /// Get the set of devices that have been discovered
     pub fn devices(&self) -> HashSet<DeviceRecord> {
-        self.device_records.lock().unwrap().clone()
+        self.device_records
+            .lock()
+            .unwrap()
+            .values()
+            .map(|entry| entry.record.clone())
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::net::{IpAddr, Ipv4Addr};
+
+    fn sample_record() -> DeviceRecord {
+        DeviceRecord {
+            ptr: Some("Living Room._googlecast._tcp.local".to_string()),
+            addresses: vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 42))],
+            port: 8009,
+            device_uuid: Some("abc123".to_string()),
+            model: None,
+            version: None,
+            icon_path: None,
+            certificate_authority: None,
+            friendly_name: None,
+            txt: std::collections::BTreeMap::new(),
+        }
+    }
+
+    #[test]
+    fn cache_entry_is_expired_boundary() {
+        let grace_multiplier = 2;
+        let ttl = Duration::from_secs(10);
+
+        let not_yet_expired = CacheEntry {
+            record: sample_record(),
+            inserted: Instant::now() - (ttl * grace_multiplier) + Duration::from_millis(500),
+            ttl,
+        };
+        assert!(!not_yet_expired.is_expired(grace_multiplier));
+
+        let expired = CacheEntry {
+            record: sample_record(),
+            inserted: Instant::now() - (ttl * grace_multiplier) - Duration::from_millis(500),
+            ttl,
+        };
+        assert!(expired.is_expired(grace_multiplier));
+    }
+
+    fn record(name: &str, ttl: u32, kind: mdns::RecordKind) -> mdns::Record {
+        mdns::Record {
+            name: name.to_string(),
+            class: mdns::Class::IN,
+            ttl,
+            kind,
+        }
+    }
+
+    fn response(answers: Vec<mdns::Record>) -> mdns::Response {
+        mdns::Response {
+            answers,
+            nameservers: Vec::new(),
+            additional: Vec::new(),
+        }
+    }
+
+    #[test]
+    fn partial_survives_a_txt_only_update() {
+        let partial_records = Mutex::new(HashMap::new());
+        let hostname_aliases = Mutex::new(HashMap::new());
+
+        let instance = "Living Room._googlecast._tcp.local";
+        let hostname = "living-room-abcd.local";
+
+        let announcement = response(vec![
+            record(
+                instance,
+                120,
+                mdns::RecordKind::SRV {
+                    priority: 0,
+                    weight: 0,
+                    port: 8009,
+                    target: hostname.to_string(),
+                },
+            ),
+            record(
+                hostname,
+                120,
+                mdns::RecordKind::A(Ipv4Addr::new(192, 168, 1, 42)),
+            ),
+            record(
+                instance,
+                120,
+                mdns::RecordKind::TXT(vec!["id=abc123".to_string()]),
+            ),
+        ]);
+
+        let first = DeviceDiscoveryCache::merge_response(
+            &partial_records,
+            &hostname_aliases,
+            &announcement,
+            IpVersion::Both,
+        );
+        assert!(matches!(first, Some(MergeOutcome::Ready(_, _))));
+
+        let txt_only_update = response(vec![record(
+            instance,
+            120,
+            mdns::RecordKind::TXT(vec!["fn=Living Room".to_string()]),
+        )]);
+
+        let second = DeviceDiscoveryCache::merge_response(
+            &partial_records,
+            &hostname_aliases,
+            &txt_only_update,
+            IpVersion::Both,
+        );
+
+        match second {
+            Some(MergeOutcome::Ready(record, _)) => {
+                assert_eq!(record.port, 8009);
+                assert_eq!(
+                    record.addresses,
+                    vec![IpAddr::V4(Ipv4Addr::new(192, 168, 1, 42))]
+                );
+                assert_eq!(record.friendly_name.as_deref(), Some("Living Room"));
+            }
+            _ => panic!("expected a TXT-only update to still finish a device record"),
+        }
     }
 }
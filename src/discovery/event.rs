@@ -0,0 +1,16 @@
+use crate::discovery::device::DeviceRecord;
+
+/// A change in the set of devices known to a [`DeviceDiscoveryCache`]
+///
+/// [`DeviceDiscoveryCache`]: crate::discovery::DeviceDiscoveryCache
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum DiscoveryEvent {
+    /// A device was seen for the first time
+    Added(DeviceRecord),
+
+    /// A previously-seen device re-announced with changed fields
+    Updated(DeviceRecord),
+
+    /// A device was evicted from the cache, e.g. after its TTL expired
+    Removed(DeviceRecord),
+}
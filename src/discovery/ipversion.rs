@@ -0,0 +1,21 @@
+/// Which IP address family to discover and prefer for a device
+///
+/// Dual-stack devices advertise both an A and an AAAA record; this lets a
+/// [`DeviceDiscoveryCache`](crate::discovery::DeviceDiscoveryCache) be
+/// restricted to one family, or keep both and let callers choose.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum IpVersion {
+    V4,
+    V6,
+    Both,
+}
+
+impl IpVersion {
+    pub(crate) fn accepts_v4(self) -> bool {
+        matches!(self, IpVersion::V4 | IpVersion::Both)
+    }
+
+    pub(crate) fn accepts_v6(self) -> bool {
+        matches!(self, IpVersion::V6 | IpVersion::Both)
+    }
+}
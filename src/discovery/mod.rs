@@ -1,7 +1,11 @@
 mod device;
 mod discoverycache;
+mod event;
+mod ipversion;
 mod services;
 
-pub use device::DeviceRecord;
+pub use device::{DeviceRecord, PartialDeviceRecord};
 pub use discoverycache::DeviceDiscoveryCache;
+pub use event::DiscoveryEvent;
+pub use ipversion::IpVersion;
 pub use services::DiscoverServices;
@@ -1,13 +1,21 @@
 /// Well-known mDNS-discoverable services
 pub enum DiscoverServices {
     GoogleCast,
+    HomeKit,
+    SpotifyConnect,
+
+    /// Any other mDNS service type, given as its full service name, e.g.
+    /// `_airplay._tcp.local`
+    Custom(String),
 }
 
 impl DiscoverServices {
     pub fn service_string(&self) -> String {
-        let name = match *self {
-            DiscoverServices::GoogleCast => "_googlecast._tcp.local",
-        };
-        String::from(name)
+        match self {
+            DiscoverServices::GoogleCast => String::from("_googlecast._tcp.local"),
+            DiscoverServices::HomeKit => String::from("_hap._tcp.local"),
+            DiscoverServices::SpotifyConnect => String::from("_spotify-connect._tcp.local"),
+            DiscoverServices::Custom(name) => name.clone(),
+        }
     }
 }